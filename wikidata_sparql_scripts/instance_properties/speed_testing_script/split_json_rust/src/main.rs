@@ -1,21 +1,600 @@
+use clap::Parser;
+use sha2::{Digest, Sha256};
 use std::fs::{self, File};
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-const CHUNK_SIZE: usize = 500_000;
+const DEFAULT_CHUNK_ENTRIES: usize = 500_000;
 const BUFFER_SIZE: usize = 64 * 1024;
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Split a large top-level JSON object into numbered batch files.
+#[derive(Parser)]
+#[command(name = "human_rights", about = "Split a large top-level JSON object into numbered batch files")]
+struct Cli {
+    /// Path to the source JSON file. A `.zst` extension is decompressed transparently.
+    #[arg(long)]
+    input: String,
+
+    /// Directory batch files (and manifest.json) are written to.
+    #[arg(long = "out")]
+    output_dir: String,
+
+    /// Close the current batch once it holds this many entries.
+    #[arg(long, default_value_t = DEFAULT_CHUNK_ENTRIES)]
+    chunk_entries: usize,
+
+    /// Close the current batch once its written byte count exceeds this many
+    /// bytes instead of counting entries. Overrides --chunk-entries when set.
+    #[arg(long)]
+    chunk_bytes: Option<u64>,
+
+    /// Worker threads for the parallel path. Ignored (forced to 1) for zstd
+    /// input. Defaults to available parallelism.
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Write batches as newline-delimited JSON instead of a pretty JSON array.
+    #[arg(long)]
+    ndjson: bool,
+
+    /// Compress each batch file with zstd.
+    #[arg(long)]
+    zstd_output: bool,
+
+    /// zstd compression level used when --zstd-output is set.
+    #[arg(long, default_value_t = DEFAULT_ZSTD_LEVEL)]
+    zstd_level: i32,
+}
+
+/// When to close the current batch and start a new one.
+#[derive(Clone, Copy)]
+enum Rollover {
+    Entries(usize),
+    Bytes(u64),
+}
+
+impl Rollover {
+    fn should_close(&self, entry_count: usize, bytes_written: u64) -> bool {
+        match self {
+            Rollover::Entries(n) => entry_count >= *n,
+            Rollover::Bytes(n) => bytes_written >= *n,
+        }
+    }
+}
+
+/// How a batch of entries should be written out: when to roll over to the next
+/// file, which format to write, and whether/how to compress it. Bundled into
+/// one struct and threaded by reference so adding another output knob doesn't
+/// mean adding another parameter to every function along the way.
+#[derive(Clone, Copy)]
+struct BatchOptions {
+    rollover: Rollover,
+    ndjson: bool,
+    zstd_output: bool,
+    zstd_level: i32,
+}
+
+/// Fail fast and clearly if the input can't be opened, instead of surfacing a
+/// bare OS error once the index/streaming pass gets underway.
+fn validate_input(path: &str) -> std::io::Result<()> {
+    File::open(path).map(|_| ()).map_err(|e| {
+        std::io::Error::new(e.kind(), format!("cannot open input file '{}': {}", path, e))
+    })
+}
+
+/// Fail fast if the output directory can't be created or isn't writable,
+/// instead of discovering it partway through a multi-gigabyte run.
+fn ensure_output_dir_writable(dir: &str) -> std::io::Result<()> {
+    fs::create_dir_all(dir).map_err(|e| {
+        std::io::Error::new(e.kind(), format!("cannot create output directory '{}': {}", dir, e))
+    })?;
+    let probe = format!("{}/.write_test", dir);
+    File::create(&probe)
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|e| {
+            std::io::Error::new(e.kind(), format!("output directory '{}' is not writable: {}", dir, e))
+        })
+}
+
+/// Wraps a reader to track bytes pulled through it and how long that took, so a
+/// progress line can show throughput and an ETA instead of a bare percentage.
+/// `total_size` is `None` when the true size isn't knowable up front (e.g. a
+/// zstd-compressed input, whose decompressed length we only learn by reading it).
+struct ReaderWithSize<R> {
+    inner: R,
+    total_read: u64,
+    total_size: Option<u64>,
+    read_start_time: Instant,
+}
+
+impl<R: Read> ReaderWithSize<R> {
+    fn new(inner: R, total_size: Option<u64>) -> Self {
+        ReaderWithSize {
+            inner,
+            total_read: 0,
+            total_size,
+            read_start_time: Instant::now(),
+        }
+    }
+
+    fn fraction(&self) -> Option<f64> {
+        self.total_size.map(|size| {
+            if size == 0 {
+                1.0
+            } else {
+                (self.total_read as f64 / size as f64).min(1.0)
+            }
+        })
+    }
+
+    fn bytes_per_sec(&self) -> f64 {
+        let elapsed = self.read_start_time.elapsed().as_secs_f64();
+        if elapsed <= 0.0 {
+            0.0
+        } else {
+            self.total_read as f64 / elapsed
+        }
+    }
+
+    /// `None` until at least a second of reads has elapsed or the size is unknown,
+    /// so the estimate isn't wildly skewed by the first few buffer fills.
+    fn eta(&self) -> Option<Duration> {
+        let total_size = self.total_size?;
+        if self.read_start_time.elapsed().as_secs_f64() < 1.0 {
+            return None;
+        }
+        let rate = self.bytes_per_sec();
+        if rate <= 0.0 {
+            return None;
+        }
+        let remaining = total_size.saturating_sub(self.total_read) as f64;
+        Some(Duration::from_secs_f64(remaining / rate))
+    }
+}
+
+impl<R: Read> Read for ReaderWithSize<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.total_read += n as u64;
+        Ok(n)
+    }
+}
+
+fn format_rate(bytes_per_sec: f64) -> String {
+    format!("{:.0} MB/s", bytes_per_sec / (1024.0 * 1024.0))
+}
+
+fn format_eta(eta: Option<Duration>) -> String {
+    match eta {
+        None => "--".to_string(),
+        Some(d) => {
+            let secs = d.as_secs();
+            format!("{:02}:{:02}:{:02}", secs / 3600, (secs % 3600) / 60, secs % 60)
+        }
+    }
+}
+
+/// Render the shared `Progress: ... | ... MB/s | ETA ... | Entries: N` line.
+fn print_progress<R: Read>(reader: &ReaderWithSize<R>, total_entries: usize) {
+    let percent = match reader.fraction() {
+        Some(f) => format!("{:.0}%", f * 100.0),
+        None => "--".to_string(),
+    };
+    print!(
+        "\rProgress: {} | {} | ETA {} | Entries: {}   ",
+        percent,
+        format_rate(reader.bytes_per_sec()),
+        format_eta(reader.eta()),
+        total_entries
+    );
+    let _ = std::io::stdout().flush();
+}
+
+/// `process_group`'s analogue of `print_progress`, for a worker that seeks around
+/// a known byte range instead of streaming through a `Read`. Unlike `print_progress`,
+/// this prints a whole line with a trailing newline rather than a bare `\r` update:
+/// several workers print concurrently, and carriage-return redraws from one thread
+/// would overwrite or interleave with another's, garbling the terminal.
+fn print_group_progress(
+    thread_id: usize,
+    bytes_done: u64,
+    total_bytes: u64,
+    start_time: Instant,
+    total_entries: usize,
+) {
+    let percent = if total_bytes == 0 {
+        100.0
+    } else {
+        (bytes_done as f64 / total_bytes as f64).min(1.0) * 100.0
+    };
+    let elapsed = start_time.elapsed().as_secs_f64();
+    let bytes_per_sec = if elapsed <= 0.0 { 0.0 } else { bytes_done as f64 / elapsed };
+    let eta = if elapsed < 1.0 || bytes_per_sec <= 0.0 {
+        None
+    } else {
+        let remaining = total_bytes.saturating_sub(bytes_done) as f64;
+        Some(Duration::from_secs_f64(remaining / bytes_per_sec))
+    };
+    println!(
+        "[thread {:02}] Progress: {:.0}% | {} | ETA {} | Entries: {}",
+        thread_id,
+        percent,
+        format_rate(bytes_per_sec),
+        format_eta(eta),
+        total_entries
+    );
+}
+
+/// The innermost writer layer, sitting below `BufWriter` and (when compressing)
+/// below the zstd encoder, so the SHA-256 digest is folded in on exactly the
+/// bytes that land on disk. Hashing any higher up the stack would, for a
+/// `--zstd-output` batch, hash the uncompressed bytes handed to the zstd
+/// encoder rather than the compressed bytes it actually writes out.
+struct HashingSink {
+    inner: File,
+    hasher: Sha256,
+}
+
+impl HashingSink {
+    fn new(inner: File) -> Self {
+        HashingSink {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+}
+
+impl Write for HashingSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A batch output file, transparently plain or zstd-compressed depending on
+/// `--zstd-output`. `finish()` must be called instead of a bare `flush()` so the
+/// zstd frame gets its final block, and it returns the hex-encoded SHA-256 of
+/// the bytes written to disk, via the `HashingSink` underneath both variants.
+enum BatchWriter {
+    Plain(BufWriter<HashingSink>),
+    Zstd(zstd::Encoder<'static, BufWriter<HashingSink>>),
+}
+
+struct HashingBatchWriter {
+    inner: BatchWriter,
+    bytes_written: u64,
+}
+
+impl BatchWriter {
+    fn create(path: &str, options: &BatchOptions) -> std::io::Result<Self> {
+        let sink = BufWriter::new(HashingSink::new(File::create(path)?));
+        if options.zstd_output {
+            Ok(BatchWriter::Zstd(zstd::Encoder::new(sink, options.zstd_level)?))
+        } else {
+            Ok(BatchWriter::Plain(sink))
+        }
+    }
+
+    fn finish(self) -> std::io::Result<String> {
+        let mut sink = match self {
+            BatchWriter::Plain(w) => w,
+            BatchWriter::Zstd(enc) => enc.finish()?,
+        };
+        sink.flush()?;
+        let hasher = sink.into_inner().map_err(|e| e.into_error())?.hasher;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+impl Write for BatchWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            BatchWriter::Plain(w) => w.write(buf),
+            BatchWriter::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            BatchWriter::Plain(w) => w.flush(),
+            BatchWriter::Zstd(w) => w.flush(),
+        }
+    }
+}
+
+impl HashingBatchWriter {
+    fn create(path: &str, options: &BatchOptions) -> std::io::Result<Self> {
+        Ok(HashingBatchWriter {
+            inner: BatchWriter::create(path, options)?,
+            bytes_written: 0,
+        })
+    }
+
+    /// Finish the batch and return the hex-encoded SHA-256 of everything written to disk.
+    fn finish(self) -> std::io::Result<String> {
+        self.inner.finish()
+    }
+}
+
+impl Write for HashingBatchWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes_written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Name a batch file: `.ndjson` instead of `.json` in NDJSON mode, plus a `.zst`
+/// suffix when output compression is on.
+fn batch_path(output_dir: &str, stem: &str, options: &BatchOptions) -> String {
+    let ext = if options.ndjson { "ndjson" } else { "json" };
+    if options.zstd_output {
+        format!("{}/{}.{}.zst", output_dir, stem, ext)
+    } else {
+        format!("{}/{}.{}", output_dir, stem, ext)
+    }
+}
+
+/// JSON forbids a raw, unescaped newline or carriage return inside a string
+/// literal, so any such bytes present only came from pretty-printing the source
+/// file. Strip them so each entry fits on the single line NDJSON requires.
+fn strip_newlines(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().copied().filter(|&b| b != b'\n' && b != b'\r').collect()
+}
+
+/// Write the start of a batch file: the wrapping `{` in pretty mode, nothing in
+/// NDJSON mode (there's no enclosing array/object to open).
+fn write_batch_header(out: &mut impl Write, ndjson: bool) -> std::io::Result<()> {
+    if !ndjson {
+        writeln!(out, "{{")?;
+    }
+    Ok(())
+}
+
+/// Write the end of a batch file: the closing `}` in pretty mode, nothing in
+/// NDJSON mode.
+fn write_batch_footer(out: &mut impl Write, ndjson: bool) -> std::io::Result<()> {
+    if !ndjson {
+        writeln!(out, "\n}}")?;
+    }
+    Ok(())
+}
+
+/// Append one entry to a batch file: pretty mode needs a leading comma for every
+/// entry but the first, plus raw bytes; NDJSON mode needs no comma (each line
+/// stands alone), but `entry` is only a `"key": value` pair, not a self-contained
+/// JSON value, so it's wrapped in `{`/`}` to make each line independently parseable.
+fn write_entry(out: &mut impl Write, options: &BatchOptions, first_in_file: bool, entry: &[u8]) -> std::io::Result<()> {
+    if options.ndjson {
+        out.write_all(b"{")?;
+        out.write_all(&strip_newlines(entry))?;
+        out.write_all(b"}\n")
+    } else {
+        if !first_in_file {
+            writeln!(out, ",")?;
+        }
+        out.write_all(entry)
+    }
+}
+
+/// One completed batch file's record in `manifest.json`: enough for a downstream
+/// consumer to detect a truncated or corrupted batch, or skip re-generating a
+/// batch whose hash already matches.
+struct ManifestEntry {
+    file: String,
+    entry_count: usize,
+    source_offset_start: u64,
+    source_offset_stop: u64,
+    sha256: String,
+}
+
+impl ManifestEntry {
+    fn to_json(&self) -> String {
+        format!(
+            "  {{\"file\": \"{}\", \"entry_count\": {}, \"source_offset_start\": {}, \"source_offset_stop\": {}, \"sha256\": \"{}\"}}",
+            self.file, self.entry_count, self.source_offset_start, self.source_offset_stop, self.sha256
+        )
+    }
+}
+
+/// Write `manifest.json` as a JSON array of `ManifestEntry` records.
+fn write_manifest(output_dir: &str, entries: &[ManifestEntry]) -> std::io::Result<()> {
+    let path = format!("{}/manifest.json", output_dir);
+    let mut out = BufWriter::new(File::create(&path)?);
+    writeln!(out, "[")?;
+    for (i, entry) in entries.iter().enumerate() {
+        if i > 0 {
+            writeln!(out, ",")?;
+        }
+        write!(out, "{}", entry.to_json())?;
+    }
+    writeln!(out, "\n]")?;
+    out.flush()
+}
+
+/// Incrementally appends manifest entries to `manifest.json` as batches close, so
+/// a crash mid-run still leaves a manifest covering everything completed so far.
+/// Used by the single-threaded (zstd input) path, where batch files get their
+/// final name the moment they're created.
+struct ManifestWriter {
+    out: BufWriter<File>,
+    wrote_any: bool,
+}
+
+impl ManifestWriter {
+    fn create(output_dir: &str) -> std::io::Result<Self> {
+        let path = format!("{}/manifest.json", output_dir);
+        let mut out = BufWriter::new(File::create(&path)?);
+        writeln!(out, "[")?;
+        Ok(ManifestWriter {
+            out,
+            wrote_any: false,
+        })
+    }
+
+    fn append(&mut self, entry: &ManifestEntry) -> std::io::Result<()> {
+        if self.wrote_any {
+            writeln!(self.out, ",")?;
+        }
+        write!(self.out, "{}", entry.to_json())?;
+        self.out.flush()?;
+        self.wrote_any = true;
+        Ok(())
+    }
+
+    fn close(mut self) -> std::io::Result<()> {
+        writeln!(self.out, "\n]")?;
+        self.out.flush()
+    }
+}
+
+/// Byte range `[start, stop)` in the source file covering one top-level entry,
+/// i.e. the span between two consecutive points where `brace_depth` returns to 1.
+#[derive(Clone, Copy)]
+struct FileChunk {
+    start: u64,
+    stop: u64,
+}
+
+/// What a single worker thread produced: the temporary batch files it wrote
+/// (already in order) and how many entries each one holds.
+struct WorkerOutput {
+    files: Vec<WorkerFile>,
+    entry_count: usize,
+}
+
+struct WorkerFile {
+    path: String,
+    entry_count: usize,
+    source_offset_start: u64,
+    source_offset_stop: u64,
+    sha256: String,
+}
 
 fn main() -> std::io::Result<()> {
-    let input_file = "../extracted_data.json";
-    let output_dir = "../extracted_batches_2";
+    let cli = Cli::parse();
+
+    validate_input(&cli.input)?;
+    ensure_output_dir_writable(&cli.output_dir)?;
+
+    let rollover = match cli.chunk_bytes {
+        Some(bytes) => Rollover::Bytes(bytes),
+        None => Rollover::Entries(cli.chunk_entries),
+    };
+    let options = BatchOptions {
+        rollover,
+        ndjson: cli.ndjson,
+        zstd_output: cli.zstd_output,
+        zstd_level: cli.zstd_level,
+    };
 
-    fs::create_dir_all(output_dir)?;
+    let threads = cli.threads.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+
+    // A zstd-compressed input can't be sliced by the raw byte offsets the index
+    // pass produces (those offsets are into the decompressed stream), so it runs
+    // through the original single-pass streaming path instead of the parallel one.
+    if cli.input.ends_with(".zst") {
+        println!("Detected zstd input: running single-threaded streaming mode.");
+        let total_entries = process_sequential(&cli.input, &cli.output_dir, &options)?;
+        println!("\nDone! Total: {} entries", total_entries);
+        return Ok(());
+    }
 
-    let metadata = fs::metadata(input_file)?;
+    let metadata = fs::metadata(&cli.input)?;
     let file_size = metadata.len();
     println!("File size: {} MB", file_size / 1024 / 1024);
 
+    println!("Indexing entry boundaries...");
+    let entries = index_entries(&cli.input, file_size)?;
+    println!(
+        "\nFound {} entries. Splitting across {} thread(s).",
+        entries.len(),
+        threads
+    );
+
+    let groups = partition_entries(&entries, threads);
+
+    let input_file = Arc::new(cli.input.clone());
+    let output_dir = Arc::new(cli.output_dir.clone());
+
+    let mut handles = Vec::new();
+    for (thread_id, group) in groups.into_iter().enumerate() {
+        if group.is_empty() {
+            continue;
+        }
+        let input_file = Arc::clone(&input_file);
+        let output_dir = Arc::clone(&output_dir);
+        handles.push(thread::spawn(move || {
+            process_group(thread_id, &input_file, &output_dir, &group, &options)
+        }));
+    }
+
+    // Join every handle before propagating an error: returning early here would
+    // end the process while sibling workers are still writing their
+    // `.tmp_threadNN_*` files out from under them.
+    let mut worker_outputs = Vec::new();
+    let mut first_err = None;
+    for handle in handles {
+        match handle.join().expect("worker thread panicked") {
+            Ok(output) => worker_outputs.push(output),
+            Err(e) if first_err.is_none() => first_err = Some(e),
+            Err(_) => {}
+        }
+    }
+
+    if let Some(err) = first_err {
+        // The run is failing overall, so the temp files any worker did finish
+        // will never be merged; remove them rather than leaving them behind.
+        for output in &worker_outputs {
+            for worker_file in &output.files {
+                let _ = fs::remove_file(&worker_file.path);
+            }
+        }
+        return Err(err);
+    }
+
+    let total_entries: usize = worker_outputs.iter().map(|w| w.entry_count).sum();
+    let total_files = merge_worker_batches(&cli.output_dir, worker_outputs, &options)?;
+
+    println!(
+        "\nDone! Total: {} entries in {} files",
+        total_entries, total_files
+    );
+    Ok(())
+}
+
+/// Open `input_file` for reading, transparently decompressing it if it's zstd.
+fn open_input(input_file: &str) -> std::io::Result<Box<dyn Read>> {
     let file = File::open(input_file)?;
-    let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
+    if input_file.ends_with(".zst") {
+        Ok(Box::new(zstd::Decoder::new(file)?))
+    } else {
+        Ok(Box::new(BufReader::with_capacity(BUFFER_SIZE, file)))
+    }
+}
+
+/// The original single-threaded extraction loop, used whenever the input can't be
+/// indexed and seeked (i.e. it's compressed). Progress falls back to an
+/// indeterminate percentage and ETA, since the decompressed size isn't known
+/// up front.
+fn process_sequential(input_file: &str, output_dir: &str, options: &BatchOptions) -> std::io::Result<usize> {
+    let mut reader = ReaderWithSize::new(open_input(input_file)?, None);
 
     let mut file_num: usize = 1;
     let mut entry_count: usize = 0;
@@ -23,35 +602,41 @@ fn main() -> std::io::Result<()> {
     let mut brace_depth: i32 = 0;
     let mut in_string = false;
     let mut escape_next = false;
-    let mut bytes_read: u64 = 0;
-    let mut last_percent: i32 = -1;
+    let mut last_printed_read: u64 = 0;
 
-    let mut entry_buffer = String::with_capacity(50 * 1024);
+    let mut entry_buffer: Vec<u8> = Vec::with_capacity(50 * 1024);
+    let mut manifest = ManifestWriter::create(output_dir)?;
 
-    let filename = format!("{}/extracted_data_{:03}.json", output_dir, file_num);
-    let mut out = BufWriter::new(File::create(&filename)?);
-    writeln!(out, "{{")?;
+    let mut path = batch_path(output_dir, &format!("extracted_data_{:03}", file_num), options);
+    let mut out = HashingBatchWriter::create(&path, options)?;
+    write_batch_header(&mut out, options.ndjson)?;
     let mut first_in_file = true;
+    let mut batch_offset_start = reader.total_read;
+    // Stream offset just past the most recently written entry's closing `}`.
+    let mut last_entry_stop: u64 = batch_offset_start;
 
     let mut buf = [0u8; BUFFER_SIZE];
     let mut start_idx: usize = 0;
     let mut buf_len: usize = 0;
     let mut found_start = false;
+    // Mirrors index_entries: the comma (and any whitespace) separating one
+    // entry from the next belongs to neither, so skip it instead of letting it
+    // leak into the next entry_buffer as a leading comma.
+    let mut skipping_separator = false;
 
     loop {
-        // If we've processed the current buffer, read more
         if start_idx >= buf_len {
             buf_len = reader.read(&mut buf)?;
-            if buf_len == 0 { break; }
+            if buf_len == 0 {
+                break;
+            }
             start_idx = 0;
         }
 
-        for i in start_idx..buf_len {
-            let c = buf[i];
-            bytes_read += 1;
-            start_idx = i + 1;
+        let mut consumed = start_idx;
+        for &c in &buf[start_idx..buf_len] {
+            consumed += 1;
 
-            // Still looking for initial {
             if !found_start {
                 if c == b'{' {
                     brace_depth = 1;
@@ -60,86 +645,638 @@ fn main() -> std::io::Result<()> {
                 continue;
             }
 
-            // Progress
-            let percent = ((bytes_read * 100) / file_size) as i32;
-            if percent != last_percent {
-                print!("\rProgress: {}% | Entries: {} | File: {}   ", percent, total_entries, file_num);
-                let _ = std::io::stdout().flush();
-                last_percent = percent;
+            if skipping_separator {
+                if c == b',' || c.is_ascii_whitespace() {
+                    continue;
+                }
+                skipping_separator = false;
+            }
+
+            if reader.total_read - last_printed_read >= 256 * 1024 {
+                print_progress(&reader, total_entries);
+                last_printed_read = reader.total_read;
             }
 
-            // Handle escape
             if escape_next {
-                entry_buffer.push(c as char);
+                entry_buffer.push(c);
                 escape_next = false;
                 continue;
             }
-
             if c == b'\\' && in_string {
-                entry_buffer.push(c as char);
+                entry_buffer.push(c);
                 escape_next = true;
                 continue;
             }
-
-            // Handle strings
             if c == b'"' {
                 in_string = !in_string;
-                entry_buffer.push(c as char);
+                entry_buffer.push(c);
                 continue;
             }
-
             if in_string {
-                entry_buffer.push(c as char);
+                entry_buffer.push(c);
                 continue;
             }
 
-            // Track braces
             if c == b'{' {
                 brace_depth += 1;
-                entry_buffer.push(c as char);
+                entry_buffer.push(c);
             } else if c == b'}' {
                 brace_depth -= 1;
-                entry_buffer.push(c as char);
+                entry_buffer.push(c);
 
                 if brace_depth == 1 {
-                    if !first_in_file {
-                        write!(out, ",\n")?;
-                    }
+                    write_entry(&mut out, options, first_in_file, &entry_buffer)?;
                     first_in_file = false;
-                    write!(out, "{}", entry_buffer)?;
                     entry_buffer.clear();
+                    skipping_separator = true;
+                    last_entry_stop = reader.total_read - (buf_len - consumed) as u64;
 
                     entry_count += 1;
                     total_entries += 1;
 
-                    if entry_count >= CHUNK_SIZE {
-                        writeln!(out, "\n}}")?;
-                        out.flush()?;
-                        println!("\nWrote extracted_data_{:03}.json ({} entries)", file_num, entry_count);
+                    if options.rollover.should_close(entry_count, out.bytes_written) {
+                        write_batch_footer(&mut out, options.ndjson)?;
+                        let sha256 = out.finish()?;
+                        println!("\nWrote {} ({} entries)", path, entry_count);
+                        manifest.append(&ManifestEntry {
+                            file: path.clone(),
+                            entry_count,
+                            source_offset_start: batch_offset_start,
+                            source_offset_stop: last_entry_stop,
+                            sha256,
+                        })?;
 
                         file_num += 1;
                         entry_count = 0;
-                        let new_filename = format!("{}/extracted_data_{:03}.json", output_dir, file_num);
-                        out = BufWriter::new(File::create(&new_filename)?);
-                        writeln!(out, "{{")?;
+                        path = batch_path(output_dir, &format!("extracted_data_{:03}", file_num), options);
+                        out = HashingBatchWriter::create(&path, options)?;
+                        write_batch_header(&mut out, options.ndjson)?;
                         first_in_file = true;
+                        batch_offset_start = last_entry_stop;
                     }
                 } else if brace_depth == 0 {
                     break;
                 }
             } else if brace_depth >= 1 {
-                entry_buffer.push(c as char);
+                entry_buffer.push(c);
             }
         }
+        start_idx = consumed;
     }
 
-    // Write remaining
     if entry_count > 0 {
-        writeln!(out, "\n}}")?;
-        out.flush()?;
-        println!("\nWrote extracted_data_{:03}.json ({} entries)", file_num, entry_count);
+        write_batch_footer(&mut out, options.ndjson)?;
+        let sha256 = out.finish()?;
+        println!("\nWrote {} ({} entries)", path, entry_count);
+        manifest.append(&ManifestEntry {
+            file: path.clone(),
+            entry_count,
+            source_offset_start: batch_offset_start,
+            source_offset_stop: last_entry_stop,
+            sha256,
+        })?;
     }
+    manifest.close()?;
 
-    println!("\n\nDone! Total: {} entries in {} files", total_entries, file_num);
-    Ok(())
+    Ok(total_entries)
+}
+
+/// First pass: run the same brace/string state machine as the extractor, but only
+/// to record entry boundaries instead of materializing entry contents. Once this
+/// index exists, entries can be sliced out of the file by byte offset alone, so
+/// the second pass never needs to resume the state machine mid-entry.
+fn index_entries(input_file: &str, file_size: u64) -> std::io::Result<Vec<FileChunk>> {
+    let file = File::open(input_file)?;
+    let mut reader = ReaderWithSize::new(BufReader::with_capacity(BUFFER_SIZE, file), Some(file_size));
+
+    let mut entries = Vec::new();
+    let mut brace_depth: i32 = 0;
+    let mut in_string = false;
+    let mut escape_next = false;
+    let mut bytes_read: u64 = 0;
+    let mut entry_start: u64 = 0;
+    let mut found_start = false;
+    // Between one entry's closing `}` and the next entry's key lies the source's
+    // own separating comma (and any pretty-printing whitespace). That's not part
+    // of either entry, so it must be skipped rather than folded into the next
+    // entry's byte range.
+    let mut skipping_separator = false;
+    let mut last_printed_read: u64 = 0;
+
+    let mut buf = [0u8; BUFFER_SIZE];
+    'outer: loop {
+        let buf_len = reader.read(&mut buf)?;
+        if buf_len == 0 {
+            break;
+        }
+
+        for &c in &buf[..buf_len] {
+            let offset = bytes_read;
+            bytes_read += 1;
+
+            if !found_start {
+                if c == b'{' {
+                    brace_depth = 1;
+                    found_start = true;
+                    entry_start = bytes_read;
+                }
+                continue;
+            }
+
+            if skipping_separator {
+                if c == b',' || c.is_ascii_whitespace() {
+                    continue;
+                }
+                skipping_separator = false;
+                entry_start = offset;
+            }
+
+            if reader.total_read - last_printed_read >= 256 * 1024 {
+                print_progress(&reader, entries.len());
+                last_printed_read = reader.total_read;
+            }
+
+            if escape_next {
+                escape_next = false;
+                continue;
+            }
+            if c == b'\\' && in_string {
+                escape_next = true;
+                continue;
+            }
+            if c == b'"' {
+                in_string = !in_string;
+                continue;
+            }
+            if in_string {
+                continue;
+            }
+
+            if c == b'{' {
+                brace_depth += 1;
+            } else if c == b'}' {
+                brace_depth -= 1;
+                if brace_depth == 1 {
+                    entries.push(FileChunk {
+                        start: entry_start,
+                        stop: offset + 1,
+                    });
+                    skipping_separator = true;
+                } else if brace_depth == 0 {
+                    break 'outer;
+                }
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Split the index into up to `threads` contiguous, roughly-equal groups so each
+/// worker covers a single unbroken run of entries in source order.
+fn partition_entries(entries: &[FileChunk], threads: usize) -> Vec<Vec<FileChunk>> {
+    let threads = threads.max(1);
+    let total = entries.len();
+    let base = total / threads;
+    let remainder = total % threads;
+
+    let mut groups = Vec::with_capacity(threads);
+    let mut idx = 0;
+    for t in 0..threads {
+        let size = base + if t < remainder { 1 } else { 0 };
+        groups.push(entries[idx..idx + size].to_vec());
+        idx += size;
+    }
+    groups
+}
+
+/// Process one contiguous group of entries on its own thread: open the input file
+/// independently, seek straight to each entry's known byte range, and copy it
+/// verbatim into thread-local batch files so no other thread's writes can interleave.
+fn process_group(
+    thread_id: usize,
+    input_file: &str,
+    output_dir: &str,
+    group: &[FileChunk],
+    options: &BatchOptions,
+) -> std::io::Result<WorkerOutput> {
+    let mut file = File::open(input_file)?;
+
+    let mut files = Vec::new();
+    let mut file_num: usize = 1;
+    let mut entry_count: usize = 0;
+    let mut total_entries: usize = 0;
+
+    let mut filename = batch_path(
+        output_dir,
+        &format!(".tmp_thread{:02}_{:03}", thread_id, file_num),
+        options,
+    );
+    let mut out = HashingBatchWriter::create(&filename, options)?;
+    write_batch_header(&mut out, options.ndjson)?;
+    let mut first_in_file = true;
+    let mut batch_offset_start = group.first().map(|c| c.start).unwrap_or(0);
+
+    let mut entry_buffer = Vec::with_capacity(50 * 1024);
+
+    let total_group_bytes: u64 = group.iter().map(|c| c.stop - c.start).sum();
+    let mut bytes_done: u64 = 0;
+    let mut last_printed_bytes: u64 = 0;
+    let group_start_time = Instant::now();
+
+    for chunk in group {
+        let len = (chunk.stop - chunk.start) as usize;
+        entry_buffer.resize(len, 0);
+        file.seek(SeekFrom::Start(chunk.start))?;
+        file.read_exact(&mut entry_buffer)?;
+
+        write_entry(&mut out, options, first_in_file, &entry_buffer)?;
+        first_in_file = false;
+
+        entry_count += 1;
+        total_entries += 1;
+        bytes_done += len as u64;
+
+        if bytes_done - last_printed_bytes >= 256 * 1024 {
+            print_group_progress(thread_id, bytes_done, total_group_bytes, group_start_time, total_entries);
+            last_printed_bytes = bytes_done;
+        }
+
+        if options.rollover.should_close(entry_count, out.bytes_written) {
+            write_batch_footer(&mut out, options.ndjson)?;
+            let sha256 = out.finish()?;
+            files.push(WorkerFile {
+                path: filename.clone(),
+                entry_count,
+                source_offset_start: batch_offset_start,
+                source_offset_stop: chunk.stop,
+                sha256,
+            });
+
+            file_num += 1;
+            entry_count = 0;
+            filename = batch_path(
+                output_dir,
+                &format!(".tmp_thread{:02}_{:03}", thread_id, file_num),
+                options,
+            );
+            out = HashingBatchWriter::create(&filename, options)?;
+            write_batch_header(&mut out, options.ndjson)?;
+            first_in_file = true;
+            batch_offset_start = chunk.stop;
+        }
+    }
+
+    if entry_count > 0 {
+        write_batch_footer(&mut out, options.ndjson)?;
+        let sha256 = out.finish()?;
+        files.push(WorkerFile {
+            path: filename.clone(),
+            entry_count,
+            source_offset_start: batch_offset_start,
+            source_offset_stop: group.last().map(|c| c.stop).unwrap_or(batch_offset_start),
+            sha256,
+        });
+    } else {
+        // Empty trailing file (the group's last entry closed a batch): drop it.
+        drop(out);
+        fs::remove_file(&filename)?;
+    }
+
+    print_group_progress(thread_id, bytes_done, total_group_bytes, group_start_time, total_entries);
+
+    Ok(WorkerOutput {
+        files,
+        entry_count: total_entries,
+    })
+}
+
+/// Renumber every worker's temporary batch files into the single global
+/// `extracted_data_NNN.json` sequence, in thread order (which is source order,
+/// since groups are contiguous and built from the entry index in file order).
+fn merge_worker_batches(
+    output_dir: &str,
+    worker_outputs: Vec<WorkerOutput>,
+    options: &BatchOptions,
+) -> std::io::Result<usize> {
+    let mut file_num = 1;
+    let mut manifest_entries = Vec::new();
+    for worker in worker_outputs {
+        for worker_file in worker.files {
+            let final_name = batch_path(output_dir, &format!("extracted_data_{:03}", file_num), options);
+            fs::rename(&worker_file.path, &final_name)?;
+            println!(
+                "Wrote {} ({} entries)",
+                final_name, worker_file.entry_count
+            );
+            manifest_entries.push(ManifestEntry {
+                file: final_name,
+                entry_count: worker_file.entry_count,
+                source_offset_start: worker_file.source_offset_start,
+                source_offset_stop: worker_file.source_offset_stop,
+                sha256: worker_file.sha256,
+            });
+            file_num += 1;
+        }
+    }
+    write_manifest(output_dir, &manifest_entries)?;
+    Ok(file_num - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Brace/quote balance check good enough for tests: true if `s` is a single
+    /// `{...}` object whose braces (outside strings) net to zero.
+    fn is_balanced_json_object(s: &str) -> bool {
+        let bytes = s.as_bytes();
+        if bytes.first() != Some(&b'{') || bytes.last() != Some(&b'}') {
+            return false;
+        }
+        let mut depth = 0i32;
+        let mut in_string = false;
+        let mut escape = false;
+        for &b in bytes {
+            if escape {
+                escape = false;
+                continue;
+            }
+            match b {
+                b'\\' if in_string => escape = true,
+                b'"' => in_string = !in_string,
+                b'{' if !in_string => depth += 1,
+                b'}' if !in_string => depth -= 1,
+                _ => {}
+            }
+            if depth < 0 {
+                return false;
+            }
+        }
+        depth == 0 && !in_string
+    }
+
+    /// Pull a quoted `"field": "value"` out of one manifest.json entry line.
+    fn extract_quoted_field(line: &str, field: &str) -> String {
+        let needle = format!("\"{}\": \"", field);
+        let start = line.find(&needle).unwrap() + needle.len();
+        let rest = &line[start..];
+        let end = rest.find('"').unwrap();
+        rest[..end].to_string()
+    }
+
+    /// Each test gets its own directory under the system temp dir, scoped by
+    /// test name and pid so parallel test runs don't collide.
+    fn unique_temp_dir(label: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("split_json_rust_test_{}_{}", label, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn process_sequential_preserves_multibyte_utf8() {
+        let dir = unique_temp_dir("utf8");
+        let input_path = dir.join("input.json");
+        fs::write(&input_path, "{\"e1\":{\"text\":\"héllo wörld 日本語\"}}").unwrap();
+
+        let output_dir = dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        let options = BatchOptions {
+            rollover: Rollover::Entries(1),
+            ndjson: false,
+            zstd_output: false,
+            zstd_level: DEFAULT_ZSTD_LEVEL,
+        };
+
+        process_sequential(input_path.to_str().unwrap(), output_dir.to_str().unwrap(), &options).unwrap();
+
+        let contents = fs::read_to_string(output_dir.join("extracted_data_001.json")).unwrap();
+        assert!(contents.contains("héllo wörld 日本語"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn zstd_batch_hash_matches_bytes_on_disk() {
+        let dir = unique_temp_dir("zstdhash");
+        let path = dir.join("batch.json.zst");
+        let options = BatchOptions {
+            rollover: Rollover::Entries(1),
+            ndjson: false,
+            zstd_output: true,
+            zstd_level: DEFAULT_ZSTD_LEVEL,
+        };
+
+        let mut out = HashingBatchWriter::create(path.to_str().unwrap(), &options).unwrap();
+        out.write_all(b"some batch content").unwrap();
+        let sha256 = out.finish().unwrap();
+
+        let mut hasher = Sha256::new();
+        hasher.update(fs::read(&path).unwrap());
+        let expected = format!("{:x}", hasher.finalize());
+        assert_eq!(sha256, expected);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn process_sequential_multi_batch_has_no_leading_comma() {
+        let dir = unique_temp_dir("multibatch");
+        let input_path = dir.join("input.json");
+        fs::write(&input_path, r#"{"a":{"x":1},"b":{"y":2},"c":{"z":3}}"#).unwrap();
+
+        let output_dir = dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        let options = BatchOptions {
+            rollover: Rollover::Entries(1),
+            ndjson: false,
+            zstd_output: false,
+            zstd_level: DEFAULT_ZSTD_LEVEL,
+        };
+
+        process_sequential(input_path.to_str().unwrap(), output_dir.to_str().unwrap(), &options).unwrap();
+
+        for (file_num, key) in [(1, "a"), (2, "b"), (3, "c")] {
+            let contents = fs::read_to_string(output_dir.join(format!("extracted_data_{:03}.json", file_num))).unwrap();
+            let body = contents.trim().trim_start_matches('{').trim_end_matches('}').trim();
+            assert!(!body.starts_with(','), "leading comma in batch {}: {:?}", file_num, contents);
+            assert!(body.starts_with(&format!("\"{}\"", key)), "batch {} missing key {}: {:?}", file_num, key, contents);
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ndjson_lines_are_valid_standalone_json() {
+        let dir = unique_temp_dir("ndjson");
+        let input_path = dir.join("input.json");
+        fs::write(&input_path, r#"{"a":{"x":1},"b":{"y":2},"c":{"z":3}}"#).unwrap();
+
+        let output_dir = dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        let options = BatchOptions {
+            rollover: Rollover::Entries(1),
+            ndjson: true,
+            zstd_output: false,
+            zstd_level: DEFAULT_ZSTD_LEVEL,
+        };
+
+        process_sequential(input_path.to_str().unwrap(), output_dir.to_str().unwrap(), &options).unwrap();
+
+        for file_num in 1..=3 {
+            let contents =
+                fs::read_to_string(output_dir.join(format!("extracted_data_{:03}.ndjson", file_num))).unwrap();
+            let line = contents.trim_end_matches('\n');
+            assert!(
+                is_balanced_json_object(line),
+                "batch {} line is not valid standalone JSON: {:?}",
+                file_num,
+                line
+            );
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn manifest_hashes_match_multi_batch_files_on_disk() {
+        let dir = unique_temp_dir("manifest");
+        let input_path = dir.join("input.json");
+        fs::write(&input_path, r#"{"a":{"x":1},"b":{"y":2},"c":{"z":3}}"#).unwrap();
+
+        let output_dir = dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        let options = BatchOptions {
+            rollover: Rollover::Entries(1),
+            ndjson: false,
+            zstd_output: false,
+            zstd_level: DEFAULT_ZSTD_LEVEL,
+        };
+
+        process_sequential(input_path.to_str().unwrap(), output_dir.to_str().unwrap(), &options).unwrap();
+
+        let manifest = fs::read_to_string(output_dir.join("manifest.json")).unwrap();
+        let mut checked = 0;
+        for line in manifest.lines() {
+            if !line.contains("\"file\":") {
+                continue;
+            }
+            let file = extract_quoted_field(line, "file");
+            let sha256 = extract_quoted_field(line, "sha256");
+
+            let mut hasher = Sha256::new();
+            hasher.update(fs::read(&file).unwrap());
+            let expected = format!("{:x}", hasher.finalize());
+            assert_eq!(sha256, expected, "manifest hash mismatch for {}", file);
+            checked += 1;
+        }
+        assert_eq!(checked, 3);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn index_entries_finds_each_top_level_chunk_without_separators() {
+        let dir = unique_temp_dir("index");
+        let input_path = dir.join("input.json");
+        let source = r#"{"a":{"x":1},"b":{"y":2},"c":{"z":3}}"#;
+        fs::write(&input_path, source).unwrap();
+        let file_size = fs::metadata(&input_path).unwrap().len();
+
+        let entries = index_entries(input_path.to_str().unwrap(), file_size).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        let bytes = source.as_bytes();
+        for (chunk, key) in entries.iter().zip(["a", "b", "c"]) {
+            let slice = std::str::from_utf8(&bytes[chunk.start as usize..chunk.stop as usize]).unwrap();
+            assert!(!slice.starts_with(','), "chunk for {} has a leading comma: {:?}", key, slice);
+            assert!(slice.starts_with(&format!("\"{}\"", key)), "chunk {:?} doesn't start with key {}", slice, key);
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn partition_entries_covers_every_chunk_in_contiguous_order() {
+        let entries: Vec<FileChunk> = (0..7)
+            .map(|i| FileChunk { start: i * 10, stop: i * 10 + 10 })
+            .collect();
+
+        let groups = partition_entries(&entries, 3);
+        assert_eq!(groups.len(), 3);
+
+        let flattened: Vec<FileChunk> = groups.into_iter().flatten().collect();
+        assert_eq!(flattened.len(), entries.len());
+        for (a, b) in flattened.iter().zip(entries.iter()) {
+            assert_eq!(a.start, b.start);
+            assert_eq!(a.stop, b.stop);
+        }
+    }
+
+    #[test]
+    fn process_group_and_merge_worker_batches_produce_valid_multi_thread_output() {
+        let dir = unique_temp_dir("group");
+        let input_path = dir.join("input.json");
+        let source = r#"{"a":{"x":1},"b":{"y":2},"c":{"z":3},"d":{"w":4}}"#;
+        fs::write(&input_path, source).unwrap();
+        let file_size = fs::metadata(&input_path).unwrap().len();
+
+        let output_dir = dir.join("out");
+        fs::create_dir_all(&output_dir).unwrap();
+        let options = BatchOptions {
+            rollover: Rollover::Entries(1),
+            ndjson: false,
+            zstd_output: false,
+            zstd_level: DEFAULT_ZSTD_LEVEL,
+        };
+
+        let entries = index_entries(input_path.to_str().unwrap(), file_size).unwrap();
+        let groups = partition_entries(&entries, 2);
+        assert_eq!(groups.len(), 2);
+
+        let mut worker_outputs = Vec::new();
+        for (thread_id, group) in groups.into_iter().enumerate() {
+            let output = process_group(
+                thread_id,
+                input_path.to_str().unwrap(),
+                output_dir.to_str().unwrap(),
+                &group,
+                &options,
+            )
+            .unwrap();
+            worker_outputs.push(output);
+        }
+        let total_entries: usize = worker_outputs.iter().map(|w| w.entry_count).sum();
+        assert_eq!(total_entries, 4);
+
+        let file_count = merge_worker_batches(output_dir.to_str().unwrap(), worker_outputs, &options).unwrap();
+        assert_eq!(file_count, 4);
+
+        for (file_num, key) in [(1, "a"), (2, "b"), (3, "c"), (4, "d")] {
+            let contents = fs::read_to_string(output_dir.join(format!("extracted_data_{:03}.json", file_num))).unwrap();
+            let body = contents.trim().trim_start_matches('{').trim_end_matches('}').trim();
+            assert!(!body.starts_with(','), "leading comma in batch {}: {:?}", file_num, contents);
+            assert!(body.starts_with(&format!("\"{}\"", key)), "batch {} missing key {}: {:?}", file_num, key, contents);
+        }
+
+        let manifest = fs::read_to_string(output_dir.join("manifest.json")).unwrap();
+        let mut checked = 0;
+        for line in manifest.lines() {
+            if !line.contains("\"file\":") {
+                continue;
+            }
+            let file = extract_quoted_field(line, "file");
+            let sha256 = extract_quoted_field(line, "sha256");
+
+            let mut hasher = Sha256::new();
+            hasher.update(fs::read(&file).unwrap());
+            let expected = format!("{:x}", hasher.finalize());
+            assert_eq!(sha256, expected, "manifest hash mismatch for {}", file);
+            checked += 1;
+        }
+        assert_eq!(checked, 4);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
 }